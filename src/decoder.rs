@@ -0,0 +1,187 @@
+//! Pluggable backends for computing the duration of a clip.
+
+use camino::Utf8Path;
+use std::future::Future;
+use std::pin::Pin;
+use std::process::ExitStatus;
+
+/// The file extensions accepted when only the native MP3 decoder is in use.
+pub const NATIVE_EXTENSIONS: &[&str] = &["mp3"];
+
+/// The file extensions accepted when a backend capable of more than MP3 is in use.
+pub const MULTI_FORMAT_EXTENSIONS: &[&str] = &["mp3", "flac", "ogg", "wav"];
+
+type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// An error produced while computing a clip's duration.
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeError {
+    #[error("failed to read file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("native decode error: {0}")]
+    Native(#[from] mp3_duration::MP3DurationError),
+
+    #[error("failed to launch ffprobe: {0}")]
+    FfprobeSpawn(std::io::Error),
+
+    #[error("ffprobe exited with status {0}")]
+    FfprobeStatus(ExitStatus),
+
+    #[error("failed to parse ffprobe duration output `{0}`")]
+    FfprobeParse(String),
+}
+
+/// Computes the duration of a clip, in milliseconds.
+pub trait Decoder: Send + Sync {
+    fn decode<'a>(&'a self, path: &'a Utf8Path) -> BoxFuture<'a, Result<u64, DecodeError>>;
+}
+
+/// Decodes MP3 frame headers in-process via the `mp3_duration` crate.
+///
+/// This is fast and has no external dependencies, but only understands MP3 and can drift on
+/// VBR files since it estimates duration from frame headers rather than fully decoding.
+pub struct NativeDecoder;
+
+impl Decoder for NativeDecoder {
+    fn decode<'a>(&'a self, path: &'a Utf8Path) -> BoxFuture<'a, Result<u64, DecodeError>> {
+        Box::pin(async move {
+            let bytes = tokio::fs::read(path).await?;
+            let duration = mp3_duration::from_read(&mut bytes.as_slice())?;
+            Ok(duration.as_millis() as u64)
+        })
+    }
+}
+
+/// Shells out to `ffprobe` to read the container's reported duration.
+///
+/// This is slower (one process spawn per clip) but is accurate on VBR files and understands
+/// any format ffprobe does, not just MP3.
+pub struct FfprobeDecoder;
+
+impl Decoder for FfprobeDecoder {
+    fn decode<'a>(&'a self, path: &'a Utf8Path) -> BoxFuture<'a, Result<u64, DecodeError>> {
+        Box::pin(async move {
+            let output = tokio::process::Command::new("ffprobe")
+                .args([
+                    "-v",
+                    "quiet",
+                    "-show_entries",
+                    "format=duration",
+                    "-of",
+                    "default=noprint_wrappers=1:nokey=1",
+                ])
+                .arg(path.as_str())
+                .output()
+                .await
+                .map_err(DecodeError::FfprobeSpawn)?;
+
+            if !output.status.success() {
+                return Err(DecodeError::FfprobeStatus(output.status));
+            }
+
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let seconds: f64 = stdout
+                .trim()
+                .parse()
+                .map_err(|_| DecodeError::FfprobeParse(stdout.trim().to_string()))?;
+
+            Ok((seconds * 1000.0).round() as u64)
+        })
+    }
+}
+
+/// Tries the native decoder first, falling back to `ffprobe` when the native decoder errors or
+/// reports a zero duration (which usually means it was handed a non-MP3 file).
+pub struct AutoDecoder {
+    native: Box<dyn Decoder>,
+    ffprobe: Box<dyn Decoder>,
+}
+
+impl AutoDecoder {
+    pub fn new() -> Self {
+        Self {
+            native: Box::new(NativeDecoder),
+            ffprobe: Box::new(FfprobeDecoder),
+        }
+    }
+
+    /// Builds an `AutoDecoder` from arbitrary decoders, so the fallback logic can be exercised
+    /// without shelling out to `ffprobe` or reading a real MP3.
+    #[cfg(test)]
+    fn with_decoders(native: impl Decoder + 'static, ffprobe: impl Decoder + 'static) -> Self {
+        Self {
+            native: Box::new(native),
+            ffprobe: Box::new(ffprobe),
+        }
+    }
+}
+
+impl Default for AutoDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for AutoDecoder {
+    fn decode<'a>(&'a self, path: &'a Utf8Path) -> BoxFuture<'a, Result<u64, DecodeError>> {
+        Box::pin(async move {
+            match self.native.decode(path).await {
+                Ok(0) | Err(_) => self.ffprobe.decode(path).await,
+                Ok(duration) => Ok(duration),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camino::Utf8PathBuf;
+
+    /// A decoder that always returns the same fixed result, for exercising `AutoDecoder`'s
+    /// fallback logic without touching the filesystem or spawning `ffprobe`.
+    struct FixedDecoder(Result<u64, &'static str>);
+
+    impl Decoder for FixedDecoder {
+        fn decode<'a>(&'a self, _path: &'a Utf8Path) -> BoxFuture<'a, Result<u64, DecodeError>> {
+            let result = self
+                .0
+                .map_err(|msg| DecodeError::FfprobeParse(msg.to_string()));
+            Box::pin(async move { result })
+        }
+    }
+
+    fn clip_path() -> Utf8PathBuf {
+        Utf8PathBuf::from("clip.mp3")
+    }
+
+    #[tokio::test]
+    async fn prefers_native_when_it_succeeds_with_nonzero_duration() {
+        let decoder = AutoDecoder::with_decoders(FixedDecoder(Ok(1234)), FixedDecoder(Ok(9999)));
+        assert_eq!(decoder.decode(&clip_path()).await.unwrap(), 1234);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_ffprobe_on_zero_native_duration() {
+        let decoder = AutoDecoder::with_decoders(FixedDecoder(Ok(0)), FixedDecoder(Ok(5000)));
+        assert_eq!(decoder.decode(&clip_path()).await.unwrap(), 5000);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_ffprobe_on_native_error() {
+        let decoder =
+            AutoDecoder::with_decoders(FixedDecoder(Err("native broke")), FixedDecoder(Ok(42)));
+        assert_eq!(decoder.decode(&clip_path()).await.unwrap(), 42);
+    }
+
+    #[tokio::test]
+    async fn surfaces_ffprobe_error_when_both_fail() {
+        let decoder = AutoDecoder::with_decoders(
+            FixedDecoder(Err("native broke")),
+            FixedDecoder(Err("ffprobe broke")),
+        );
+        let err = decoder.decode(&clip_path()).await.unwrap_err();
+        assert!(err.to_string().contains("ffprobe broke"));
+    }
+}
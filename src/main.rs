@@ -1,27 +1,175 @@
-/// Iterates a directory for .mp3 files and records each duration and reports the total
+/// Iterates a directory for clip files and records each duration and reports the total
 ///
-/// This tool should be passed a path to a directory. It will create a file called "times.txt"
-/// next to the given directory and search it for all files with the ".mp3" file extension.
+/// This tool should be passed a path to a directory. It will create a file called
+/// "clip_durations.tsv" next to the given directory and search it recursively for clip files.
 ///
-/// For each found ".mp3", it will calculate its duration (in milliseconds) and add a line to
-/// "times.txt" with the form "`path/to/file` = 1000".
+/// By default only ".mp3" files are considered, decoded in-process via frame-header scanning.
+/// Passing `--backend ffprobe` (or `auto`, which falls back to ffprobe when the native decoder
+/// errors or returns a zero duration) shells out to `ffprobe` instead, which is slower but
+/// accurate on VBR files and also unlocks ".flac", ".ogg" and ".wav" clips.
 ///
-/// Finally, it will output the total time of all ".mp3" files in the directory (in milliseconds).
+/// For each found clip, it will calculate its duration (in milliseconds) and add a line to
+/// "clip_durations.tsv" with the form "`path/to/file`\t1000\tok", where the last column is the
+/// row's status (`ok` or `error`).
+///
+/// Finally, it will output the total time of all clips in the directory (in milliseconds).
+///
+/// Passing `--resume` reads back an existing "clip_durations.tsv" from a previous run, skips
+/// re-processing any clip already recorded in it, and continues appending — letting a crashed
+/// or cancelled run on a very large corpus be safely re-invoked instead of starting over.
+///
+/// Passing `--watch` keeps the process running after the initial scan, watching the directory
+/// tree for newly created or modified clip files and appending their durations as they appear —
+/// handy for a corpus that's still growing (e.g. clips being uploaded).
+///
+/// A decode failure is recorded as a row with an `error` status and a duration of 0 rather than
+/// silently counted as a zero-length clip. Passing `--format json` additionally prints a
+/// machine-readable summary to stdout (total milliseconds, file count, failure count, and the
+/// failed paths with their error strings) instead of just the bare total.
 ///
 /// This program uses non-blocking I/O for everything, so it should be able to handle a massive
-/// number of files with relatively few threads.
-use camino::Utf8PathBuf;
-use std::convert::TryInto;
-use tokio::io::AsyncWriteExt;
+/// number of files with relatively few threads. Directory and file reads of the initial scan are
+/// bounded by a semaphore (see `--max-concurrency`) so that traversing a massive tree doesn't
+/// exhaust the process's file descriptor limit. Clips decoded by `--watch` do not take a permit
+/// from that semaphore.
+mod decoder;
+mod resume;
+mod watch;
+
+use bytes::BytesMut;
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::{Parser, ValueEnum};
+use decoder::{AutoDecoder, Decoder, FfprobeDecoder, NativeDecoder};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::Semaphore;
 use tracing::Instrument;
 
+/// The outcome of decoding a single clip, as sent over the results channel and recorded in the
+/// output TSV.
+enum ClipResult {
+    Ok(u64),
+    Error(String),
+}
+
+impl ClipResult {
+    /// The duration to record in the TSV; 0 for a failed decode.
+    fn duration_ms(&self) -> u64 {
+        match self {
+            ClipResult::Ok(duration) => *duration,
+            ClipResult::Error(_) => 0,
+        }
+    }
+
+    /// The per-row status column written to the TSV.
+    fn status(&self) -> &'static str {
+        match self {
+            ClipResult::Ok(_) => "ok",
+            ClipResult::Error(_) => "error",
+        }
+    }
+}
+
+/// Machine-readable summary emitted to stdout when `--format json` is passed.
+#[derive(serde::Serialize)]
+struct Summary {
+    total_duration_ms: u64,
+    file_count: u64,
+    failure_count: u64,
+    failures: Vec<Failure>,
+}
+
+#[derive(serde::Serialize)]
+struct Failure {
+    path: Utf8PathBuf,
+    error: String,
+}
+
 /// The max number of (path, duration) pairs that can be queued up for writing to the
 /// output file
 const CHANNEL_LEN: usize = 1000000;
 
+/// The max number of (path, duration) pairs drained from the channel at once before they're
+/// written out as a single batch
+const BATCH_LEN: usize = 1024;
+
 /// The name of the output file
 const OUT_FILE_NAME: &str = "clip_durations.tsv";
 
+/// The header line written at the top of the output file
+const TSV_HEADER: &[u8] = b"clip\tduration[ms]\tstatus\n";
+
+/// The default value of `--max-concurrency` when neither the flag nor the env var is set
+const DEFAULT_MAX_CONCURRENCY: usize = 256;
+
+/// Which decoder backend to use for computing clip durations
+#[derive(Clone, Copy, ValueEnum)]
+enum Backend {
+    /// Scan MP3 frame headers in-process. Fast, but MP3-only and can drift on VBR files.
+    Native,
+    /// Shell out to `ffprobe`. Slower, but accurate and format-agnostic.
+    Ffprobe,
+    /// Try `native` first, falling back to `ffprobe` on error or a zero duration.
+    Auto,
+}
+
+impl Backend {
+    fn build(self) -> Arc<dyn Decoder> {
+        match self {
+            Backend::Native => Arc::new(NativeDecoder),
+            Backend::Ffprobe => Arc::new(FfprobeDecoder),
+            Backend::Auto => Arc::new(AutoDecoder::new()),
+        }
+    }
+
+    /// The file extensions this backend is willing to consider.
+    fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Backend::Native => decoder::NATIVE_EXTENSIONS,
+            Backend::Ffprobe | Backend::Auto => decoder::MULTI_FORMAT_EXTENSIONS,
+        }
+    }
+}
+
+/// The format of the summary printed to stdout once processing finishes
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    /// Print just the total duration in milliseconds, as before
+    Text,
+    /// Print a JSON object with the total duration, file count, and any failures
+    Json,
+}
+
+#[derive(Parser)]
+struct Args {
+    /// Directory to recursively search for clip files
+    directory: Utf8PathBuf,
+
+    /// Maximum number of directory reads and file reads that may be in flight at once during the
+    /// initial scan (clips decoded by `--watch` are not counted)
+    #[arg(long, env = "MP3_MAX_CONCURRENCY", default_value_t = DEFAULT_MAX_CONCURRENCY)]
+    max_concurrency: usize,
+
+    /// Which decoder backend to use for computing clip durations
+    #[arg(long, value_enum, default_value_t = Backend::Native)]
+    backend: Backend,
+
+    /// After the initial scan, keep running and watch the directory (and subdirectories) for
+    /// new or modified clip files, appending their durations to the TSV as they appear
+    #[arg(long)]
+    watch: bool,
+
+    /// Resume a previous run: skip clips already recorded in an existing output file instead of
+    /// truncating it, and append only the ones that are missing
+    #[arg(long)]
+    resume: bool,
+
+    /// Format of the summary printed to stdout once processing finishes
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
 #[tokio::main]
 async fn main() {
     // Controlled by environment. Use RUST_LOG
@@ -30,30 +178,184 @@ async fn main() {
         .with_writer(std::io::stderr)
         .init();
 
-    let path = Utf8PathBuf::from_path_buf(std::path::PathBuf::from(
-        std::env::args_os().nth(1).expect("expected directory path"),
-    ))
-    .expect("directory path not UTF-8");
+    let args = Args::parse();
+    let path = args.directory;
 
     let out_path = path.parent().unwrap().join(OUT_FILE_NAME);
 
     tracing::info!("processing directory `{path}`");
     tracing::info!("output to file `{out_path}`");
+    tracing::info!("max concurrency: {}", args.max_concurrency);
+
+    let (mut out_file, already_processed, mut total) = if args.resume {
+        let state = resume::inspect(&out_path).await;
+        tracing::info!(
+            "resuming: {} clips already recorded, {} ms so far",
+            state.processed.len(),
+            state.total
+        );
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(&out_path)
+            .await
+            .expect("failed to open times file for resume");
+
+        // Rewrite the file down to just the header and previously-successful rows: a torn
+        // trailing entry from a crash is dropped, and so is any `error` row for a clip that's
+        // about to be retried, so it doesn't end up duplicated once this run records its result.
+        file.set_len(0)
+            .await
+            .expect("failed to truncate times file for rewrite");
+        file.write_all(&state.kept_bytes)
+            .await
+            .expect("failed to rewrite times file for resume");
+
+        (BufWriter::new(file), state.processed, state.total)
+    } else {
+        let mut file = BufWriter::new(
+            tokio::fs::File::create(&out_path)
+                .await
+                .expect("failed to create times file"),
+        );
 
-    let mut out_file = tokio::fs::File::create(&out_path)
-        .await
-        .expect("failed to create times file");
+        file.write_all(TSV_HEADER)
+            .await
+            .expect("failed to write header to file");
 
-    // write tsv header to out_file
-    out_file
-        .write(b"clip\tduration[ms]\n")
-        .await
-        .expect("failed to write header to file");
+        (file, HashSet::new(), 0)
+    };
 
     let (sender, mut receiver) = tokio::sync::mpsc::channel(CHANNEL_LEN);
 
-    tokio::spawn(async move {
-        let mut dir_entries = tokio::fs::read_dir(&path)
+    let semaphore = Arc::new(Semaphore::new(args.max_concurrency));
+    let decoder = args.backend.build();
+    let extensions = args.backend.extensions();
+    // Previously-recorded successes count toward the summary too, so `file_count` lines up with
+    // the cumulative `total` rather than only covering this invocation.
+    let resumed_file_count = already_processed.len() as u64;
+    let already_processed = Arc::new(already_processed);
+
+    tokio::spawn(walk_dir(
+        path.clone(),
+        path.clone(),
+        semaphore,
+        decoder.clone(),
+        extensions,
+        already_processed,
+        sender.clone(),
+    ));
+
+    let _watcher = if args.watch {
+        tracing::info!("watching `{path}` for new clips");
+        Some(
+            watch::watch(path, out_path, extensions, decoder, sender.clone())
+                .expect("failed to start directory watcher"),
+        )
+    } else {
+        None
+    };
+
+    // Drop our own handle; the channel now closes once `walk_dir` (and the watcher, if any)
+    // finish with their clones.
+    drop(sender);
+
+    let mut batch = Vec::with_capacity(BATCH_LEN);
+    let mut line_buf = BytesMut::new();
+    let mut file_count = resumed_file_count;
+    let mut failures = Vec::new();
+
+    loop {
+        batch.clear();
+        let received = receiver.recv_many(&mut batch, BATCH_LEN).await;
+        if received == 0 {
+            break;
+        }
+
+        for (path, result) in &batch {
+            let duration = result.duration_ms();
+            let status = result.status();
+
+            tracing::debug!("writing \"`{path}` = {duration} ({status})\" to file");
+            line_buf.extend_from_slice(format!("{path}\t{duration}\t{status}\n").as_bytes());
+
+            total += duration;
+            file_count += 1;
+
+            if let ClipResult::Error(error) = result {
+                failures.push(Failure {
+                    path: path.clone(),
+                    error: error.clone(),
+                });
+            }
+        }
+
+        out_file
+            .write_all_buf(&mut line_buf)
+            .await
+            .expect("failed to write to time file");
+
+        if args.watch {
+            tracing::info!("running total: {total}");
+
+            // In watch mode the loop above never exits on its own, so without an explicit flush
+            // here rows sit in the `BufWriter` indefinitely and are lost if the process is
+            // killed — defeating the point of recording durations incrementally as clips appear.
+            out_file
+                .flush()
+                .await
+                .expect("failed to flush time file");
+        }
+    }
+
+    out_file.flush().await.expect("failed to flush time file");
+
+    tracing::info!("total: {total}");
+
+    match args.format {
+        OutputFormat::Text => println!("{total}"),
+        OutputFormat::Json => {
+            let summary = Summary {
+                total_duration_ms: total,
+                file_count,
+                failure_count: failures.len() as u64,
+                failures,
+            };
+            println!(
+                "{}",
+                serde_json::to_string(&summary).expect("failed to serialize summary")
+            );
+        }
+    }
+}
+
+/// Recursively walks `dir`, emitting `(relative_path, result)` pairs for every accepted clip
+/// file found under it into `sender`.
+///
+/// `root` is the directory the walk started from; it's used to compute the relative path that
+/// ends up in the output TSV, so that files with the same name in different subdirectories don't
+/// collide. Each directory holds a permit from `semaphore` for the entire time it's being
+/// enumerated (not just while it's opened), and each file read acquires its own permit, so the
+/// number of simultaneously open directory and file descriptors stays bounded regardless of
+/// tree size.
+fn walk_dir(
+    dir: Utf8PathBuf,
+    root: Utf8PathBuf,
+    semaphore: Arc<Semaphore>,
+    decoder: Arc<dyn Decoder>,
+    extensions: &'static [&'static str],
+    already_processed: Arc<HashSet<Utf8PathBuf>>,
+    sender: tokio::sync::mpsc::Sender<(Utf8PathBuf, ClipResult)>,
+) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>> {
+    Box::pin(async move {
+        let permit = semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore closed");
+
+        let mut dir_entries = tokio::fs::read_dir(&dir)
             .await
             .expect("path needs to exist");
 
@@ -65,56 +367,119 @@ async fn main() {
             let path = Utf8PathBuf::from_path_buf(entry.path())
                 .expect("directory contained non-UTF-8 file");
 
-            if path.extension().map(|s| s.to_ascii_lowercase()).as_deref() != Some("mp3") {
-                tracing::debug!("skipping file `{path}` (not an mp3)");
+            let file_type = entry.file_type().await.expect("failed to read file type");
+
+            if file_type.is_dir() {
+                tokio::spawn(walk_dir(
+                    path,
+                    root.clone(),
+                    semaphore.clone(),
+                    decoder.clone(),
+                    extensions,
+                    already_processed.clone(),
+                    sender.clone(),
+                ));
                 continue;
             }
 
-            let file_span = tracing::debug_span!("file_span", "`{path}`");
+            let extension = path.extension().map(|s| s.to_ascii_lowercase());
+            if !extensions.contains(&extension.as_deref().unwrap_or_default()) {
+                tracing::debug!("skipping file `{path}` (not an accepted clip format)");
+                continue;
+            }
+
+            let relative_path = relative_to(&root, &path);
+
+            if already_processed.contains(&relative_path) {
+                tracing::debug!("skipping `{relative_path}` (already recorded, resuming)");
+                continue;
+            }
+
+            let file_span = tracing::debug_span!("file_span", "`{relative_path}`");
             let sender = sender.clone();
+            let semaphore = semaphore.clone();
+            let decoder = decoder.clone();
 
             tokio::spawn(
                 async move {
-                    tracing::info!("reading mp3 file");
-
-                    let mp3_bytes = tokio::fs::read(&path)
-                        .await
-                        .expect("failed to read mp3 file");
+                    let permit = semaphore.acquire_owned().await.expect("semaphore closed");
 
-                    tracing::debug!("calculating duration");
+                    tracing::info!("decoding clip");
 
-                    let duration: u64 = match mp3_duration::from_read(&mut mp3_bytes.as_slice()) {
-                        Ok(x) => x.as_millis().try_into().unwrap(),
+                    let result = match decoder.decode(&path).await {
+                        Ok(duration) => ClipResult::Ok(duration),
                         Err(e) => {
                             tracing::error!("an error occurred: {e}");
-                            0
+                            ClipResult::Error(e.to_string())
                         }
                     };
 
-                    tracing::debug!("duration: {duration}");
+                    drop(permit);
 
-                    sender.send((path, duration)).await.unwrap();
+                    tracing::debug!("duration: {}", result.duration_ms());
+
+                    sender.send((relative_path, result)).await.unwrap();
                 }
                 .instrument(file_span),
             );
         }
-    });
+    })
+}
 
-    let mut total = 0;
+/// Returns `path` relative to `root`, falling back to `path` itself if it isn't actually nested
+/// under `root` (which shouldn't happen given how `walk_dir` recurses).
+pub(crate) fn relative_to(root: &Utf8Path, path: &Utf8Path) -> Utf8PathBuf {
+    path.strip_prefix(root)
+        .map(Utf8Path::to_path_buf)
+        .unwrap_or_else(|_| path.to_path_buf())
+}
 
-    while let Some((path, duration)) = receiver.recv().await {
-        let line = format!("{filename}\t{duration}\n", filename = path.file_name().unwrap());
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        tracing::debug!("writing \"`{path}` = {duration}\" to file");
+    #[test]
+    fn relative_to_strips_root_prefix() {
+        let root = Utf8PathBuf::from("/corpus");
+        let path = Utf8PathBuf::from("/corpus/en/clip.mp3");
+        assert_eq!(relative_to(&root, &path), Utf8PathBuf::from("en/clip.mp3"));
+    }
 
-        out_file
-            .write_all(line.as_bytes())
-            .await
-            .expect("failed to write to time file");
+    #[test]
+    fn relative_to_handles_nested_subdirectories() {
+        let root = Utf8PathBuf::from("/corpus");
+        let path = Utf8PathBuf::from("/corpus/en/validated/clip.mp3");
+        assert_eq!(
+            relative_to(&root, &path),
+            Utf8PathBuf::from("en/validated/clip.mp3")
+        );
+    }
 
-        total += duration;
+    #[test]
+    fn relative_to_falls_back_to_full_path_when_not_nested() {
+        let root = Utf8PathBuf::from("/corpus");
+        let path = Utf8PathBuf::from("/elsewhere/clip.mp3");
+        assert_eq!(relative_to(&root, &path), path);
     }
 
-    tracing::info!("total: {total}");
-    println!("{total}");
+    #[test]
+    fn summary_serializes_with_expected_shape() {
+        let summary = Summary {
+            total_duration_ms: 1500,
+            file_count: 2,
+            failure_count: 1,
+            failures: vec![Failure {
+                path: Utf8PathBuf::from("en/bad.mp3"),
+                error: "native decode error: boom".to_string(),
+            }],
+        };
+
+        let json = serde_json::to_value(&summary).expect("failed to serialize summary");
+
+        assert_eq!(json["total_duration_ms"], 1500);
+        assert_eq!(json["file_count"], 2);
+        assert_eq!(json["failure_count"], 1);
+        assert_eq!(json["failures"][0]["path"], "en/bad.mp3");
+        assert_eq!(json["failures"][0]["error"], "native decode error: boom");
+    }
 }
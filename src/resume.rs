@@ -0,0 +1,200 @@
+//! Support for `--resume`: inspecting a TSV from a previous (possibly interrupted) run so a
+//! re-invocation only processes clips that haven't been recorded yet.
+
+use camino::Utf8PathBuf;
+use std::collections::HashSet;
+
+/// The header line written at the top of every `clip_durations.tsv`.
+const HEADER: &[u8] = b"clip\tduration[ms]\tstatus\n";
+
+/// What a previous run's TSV tells us about where to pick up from.
+pub struct ResumeState {
+    /// Relative paths of clips already successfully recorded, so the walk can skip them. Clips
+    /// that previously failed (`status=error`) are deliberately left out so they get retried.
+    pub processed: HashSet<Utf8PathBuf>,
+    /// The sum of durations already successfully recorded.
+    pub total: u64,
+    /// What the output file should be rewritten to before appending new rows: the header
+    /// followed by every previously-successful row. A torn trailing entry from a previous crash
+    /// and rows for clips that are about to be retried (because they failed last time) are both
+    /// left out, so a retried clip doesn't end up with two conflicting rows once this run adds
+    /// its own.
+    pub kept_bytes: Vec<u8>,
+}
+
+/// Reads and parses an existing `clip_durations.tsv`, if any. Returns a fresh, empty
+/// `ResumeState` if the file doesn't exist yet.
+pub async fn inspect(out_path: &camino::Utf8Path) -> ResumeState {
+    let bytes = match tokio::fs::read(out_path).await {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return ResumeState {
+                processed: HashSet::new(),
+                total: 0,
+                kept_bytes: HEADER.to_vec(),
+            };
+        }
+        Err(e) => panic!("failed to read existing times file for resume: {e}"),
+    };
+
+    // Only the very last row can be torn (a crash mid-write only ever corrupts the row being
+    // written at the time), so find the last newline and split there *before* decoding anything
+    // as UTF-8 — a crash can tear a multi-byte character just as easily as a whole row, and
+    // decoding the raw torn tail as UTF-8 would panic.
+    let split = bytes.iter().rposition(|&b| b == b'\n').map(|idx| idx + 1);
+    let (complete, torn) = match split {
+        Some(idx) => bytes.split_at(idx),
+        None => (&[][..], &bytes[..]),
+    };
+
+    if !torn.is_empty() {
+        tracing::warn!(
+            "discarding torn trailing entry from a previous run: {:?}",
+            String::from_utf8_lossy(torn)
+        );
+    }
+
+    let complete = std::str::from_utf8(complete)
+        .expect("complete rows in existing times file are not valid UTF-8");
+
+    let mut processed = HashSet::new();
+    let mut total = 0u64;
+    let mut kept_bytes = HEADER.to_vec();
+
+    for line in complete.lines() {
+        if line.as_bytes() == &HEADER[..HEADER.len() - 1] {
+            continue;
+        }
+
+        let mut fields = line.splitn(3, '\t');
+        let (Some(name), Some(duration), Some(status)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            tracing::warn!("skipping malformed row in existing times file: {line:?}");
+            continue;
+        };
+
+        let Ok(duration) = duration.parse::<u64>() else {
+            tracing::warn!("skipping malformed row in existing times file: {line:?}");
+            continue;
+        };
+
+        // Only a successful clip counts as "already recorded" — a clip that errored out last
+        // time is retried instead, and its stale row is left out of `kept_bytes` so it doesn't
+        // end up duplicated alongside the new one.
+        if status == "ok" {
+            processed.insert(Utf8PathBuf::from(name));
+            total += duration;
+            kept_bytes.extend_from_slice(line.as_bytes());
+            kept_bytes.push(b'\n');
+        }
+    }
+
+    ResumeState {
+        processed,
+        total,
+        kept_bytes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write as _;
+
+    /// Writes `contents` to a uniquely-named file in the OS temp dir and returns its path.
+    fn write_tsv(name: &str, contents: &[u8]) -> Utf8PathBuf {
+        let path = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mp3_duration_reporter_resume_test_{name}.tsv"));
+        std::fs::File::create(&path).unwrap().write_all(contents).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn detects_existing_header() {
+        let path = write_tsv("header", b"clip\tduration[ms]\tstatus\na.mp3\t1000\tok\n");
+        let state = inspect(&path).await;
+        assert_eq!(state.total, 1000);
+        assert!(state.processed.contains(&Utf8PathBuf::from("a.mp3")));
+        assert_eq!(
+            state.kept_bytes,
+            b"clip\tduration[ms]\tstatus\na.mp3\t1000\tok\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_header_is_tolerated() {
+        let path = write_tsv("no_header", b"a.mp3\t1000\tok\n");
+        let state = inspect(&path).await;
+        assert_eq!(state.total, 1000);
+        assert!(state.processed.contains(&Utf8PathBuf::from("a.mp3")));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn torn_trailing_row_is_discarded() {
+        let path = write_tsv(
+            "torn",
+            b"clip\tduration[ms]\tstatus\na.mp3\t1000\tok\nb.mp3\t50",
+        );
+        let state = inspect(&path).await;
+        assert_eq!(state.total, 1000);
+        assert!(state.processed.contains(&Utf8PathBuf::from("a.mp3")));
+        assert!(!state.processed.contains(&Utf8PathBuf::from("b.mp3")));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn torn_trailing_row_with_invalid_utf8_does_not_panic() {
+        let mut contents = b"clip\tduration[ms]\tstatus\na.mp3\t1000\tok\n".to_vec();
+        // The first two bytes of a three-byte sequence: not valid UTF-8 on their own,
+        // simulating a multi-byte character torn mid-write by a crash.
+        contents.extend_from_slice(b"b.mp3\t\xE2\x98");
+        let path = write_tsv("torn_invalid_utf8", &contents);
+        let state = inspect(&path).await;
+        assert_eq!(state.total, 1000);
+        assert!(state.processed.contains(&Utf8PathBuf::from("a.mp3")));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn malformed_row_is_skipped() {
+        let path = write_tsv(
+            "malformed",
+            b"clip\tduration[ms]\tstatus\nnotanumber\tbad\nb.mp3\t200\tok\n",
+        );
+        let state = inspect(&path).await;
+        assert_eq!(state.total, 200);
+        assert!(state.processed.contains(&Utf8PathBuf::from("b.mp3")));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn error_rows_are_dropped_from_processed_and_kept_bytes() {
+        let path = write_tsv(
+            "error_row",
+            b"clip\tduration[ms]\tstatus\na.mp3\t0\terror\nb.mp3\t200\tok\n",
+        );
+        let state = inspect(&path).await;
+        assert!(!state.processed.contains(&Utf8PathBuf::from("a.mp3")));
+        assert!(state.processed.contains(&Utf8PathBuf::from("b.mp3")));
+        assert_eq!(
+            state.kept_bytes,
+            b"clip\tduration[ms]\tstatus\nb.mp3\t200\tok\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_file_yields_empty_state() {
+        let path = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join("mp3_duration_reporter_resume_test_does_not_exist.tsv");
+        let state = inspect(&path).await;
+        assert!(state.processed.is_empty());
+        assert_eq!(state.total, 0);
+        assert_eq!(state.kept_bytes, HEADER);
+    }
+}
@@ -0,0 +1,119 @@
+//! Watches a directory tree for newly created or modified clip files after the initial scan,
+//! feeding them into the same duration pipeline as they appear.
+
+use crate::decoder::{DecodeError, Decoder};
+use crate::ClipResult;
+use camino::{Utf8Path, Utf8PathBuf};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// How long to wait before (re)trying to decode a file that was just created or modified, to
+/// give a writer time to finish. A newly-created file is often still being written to when the
+/// create event fires.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Number of times to retry decoding a file that appears to still be mid-write.
+const MAX_RETRIES: u32 = 5;
+
+/// Watches `root` (and its subdirectories) for created or modified clip files, decoding each
+/// one and sending `(relative_path, result)` into `sender` as it's found, where `result` is the
+/// duration in milliseconds or the decode error once retries are exhausted. The returned
+/// watcher must be kept alive for watching to continue; dropping it stops the watch.
+///
+/// `out_path` is skipped so that the tool doesn't react to its own output file.
+pub fn watch(
+    root: Utf8PathBuf,
+    out_path: Utf8PathBuf,
+    extensions: &'static [&'static str],
+    decoder: Arc<dyn Decoder>,
+    sender: mpsc::Sender<(Utf8PathBuf, ClipResult)>,
+) -> notify::Result<RecommendedWatcher> {
+    let (raw_sender, mut raw_receiver) = mpsc::unbounded_channel();
+
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| match res {
+        Ok(event) => {
+            let _ = raw_sender.send(event);
+        }
+        Err(e) => tracing::error!("watch error: {e}"),
+    })?;
+
+    watcher.watch(root.as_std_path(), RecursiveMode::Recursive)?;
+
+    // A single file write (e.g. `cp`) typically fires more than one create/modify event before
+    // the writer closes the file. Track paths currently being decoded so a burst of events for
+    // the same clip only produces one TSV row instead of one per event.
+    let pending: Arc<Mutex<HashSet<Utf8PathBuf>>> = Arc::new(Mutex::new(HashSet::new()));
+
+    tokio::spawn(async move {
+        while let Some(event) = raw_receiver.recv().await {
+            if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                continue;
+            }
+
+            for path in event.paths {
+                let Ok(path) = Utf8PathBuf::from_path_buf(path) else {
+                    continue;
+                };
+
+                if path == out_path {
+                    tracing::debug!("skipping `{path}` (it's the output file)");
+                    continue;
+                }
+
+                let extension = path.extension().map(|s| s.to_ascii_lowercase());
+                if !extensions.contains(&extension.as_deref().unwrap_or_default()) {
+                    continue;
+                }
+
+                let relative_path = crate::relative_to(&root, &path);
+
+                if !pending.lock().unwrap().insert(relative_path.clone()) {
+                    tracing::debug!("already decoding `{relative_path}`, ignoring duplicate event");
+                    continue;
+                }
+
+                let decoder = decoder.clone();
+                let sender = sender.clone();
+                let pending = pending.clone();
+
+                tokio::spawn(async move {
+                    let result = match decode_with_retry(&decoder, &path).await {
+                        Ok(duration) => ClipResult::Ok(duration),
+                        Err(e) => {
+                            tracing::error!("giving up decoding `{path}` after retries: {e}");
+                            ClipResult::Error(e.to_string())
+                        }
+                    };
+
+                    pending.lock().unwrap().remove(&relative_path);
+
+                    let _ = sender.send((relative_path, result)).await;
+                });
+            }
+        }
+    });
+
+    Ok(watcher)
+}
+
+/// Decodes `path`, retrying a few times with a short delay on failure — a file that was just
+/// created or modified may still be mid-write when the event fires.
+async fn decode_with_retry(decoder: &Arc<dyn Decoder>, path: &Utf8Path) -> Result<u64, DecodeError> {
+    tokio::time::sleep(RETRY_DELAY).await;
+
+    let mut attempt = 0;
+    loop {
+        match decoder.decode(path).await {
+            Ok(duration) => return Ok(duration),
+            Err(e) if attempt < MAX_RETRIES => {
+                attempt += 1;
+                tracing::debug!("retrying `{path}` after decode error ({attempt}/{MAX_RETRIES}): {e}");
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}